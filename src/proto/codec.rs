@@ -0,0 +1,549 @@
+// Copyright (c) 2015 Y. T. Chung <zonyitoo@gmail.com>
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Flag-driven value encoding, shared by every protocol implementation.
+//!
+//! Memcached reserves the 32-bit `flags` field for client use, and most language
+//! clients agree on a handful of bits to mean "this value is compressed" or
+//! "this value is a serialized object", so that values written by one client can
+//! be read back by another. [`EncodingProto`] wraps any protocol implementation
+//! and runs a [`ValueCodec`] over every stored/retrieved value, stamping and
+//! reading those bits automatically.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+
+use crate::proto::{
+    AuthOperation, AuthResponse, CasOperation, MemCachedResult, MultiOperation, NoReplyOperation, Operation,
+    ServerOperation,
+};
+
+/// Value was gzip-compressed by [`GzipCodec`].
+pub const FLAG_GZIP: u32 = 1 << 16;
+/// Value was raw-deflate-compressed by [`DeflateCodec`].
+pub const FLAG_DEFLATE: u32 = 1 << 17;
+/// Value is JSON-encoded, tagged by [`JsonCodec`].
+pub const FLAG_JSON: u32 = 1 << 18;
+/// Value is msgpack-encoded, tagged by [`MsgpackCodec`].
+pub const FLAG_MSGPACK: u32 = 1 << 19;
+
+/// Transforms values on the way into the cache and reverses the transform on the way out,
+/// recording whatever it did in the reserved bits of the `flags` field.
+pub trait ValueCodec: Send + Sync {
+    /// Encode `raw` for storage, returning the bytes to put on the wire and the codec's
+    /// reserved flag bits (to be OR-ed with the caller-supplied flags).
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32);
+
+    /// Reverse `encode`, given the flags that came back with the value.
+    fn decode(&self, data: Vec<u8>, flags: u32) -> MemCachedResult<Vec<u8>>;
+}
+
+/// Stores values exactly as given; the default when no encoding is wanted.
+pub struct RawCodec;
+
+impl ValueCodec for RawCodec {
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32) {
+        (Bytes::copy_from_slice(raw), 0)
+    }
+
+    fn decode(&self, data: Vec<u8>, _flags: u32) -> MemCachedResult<Vec<u8>> {
+        Ok(data)
+    }
+}
+
+/// Gzip-compresses values larger than `threshold` bytes, leaving smaller ones untouched.
+pub struct GzipCodec {
+    threshold: usize,
+}
+
+impl GzipCodec {
+    pub fn new(threshold: usize) -> GzipCodec {
+        GzipCodec { threshold }
+    }
+}
+
+impl ValueCodec for GzipCodec {
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32) {
+        if raw.len() <= self.threshold {
+            return (Bytes::copy_from_slice(raw), 0);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw).expect("writing to an in-memory encoder cannot fail");
+        let compressed = encoder.finish().expect("finishing an in-memory encoder cannot fail");
+
+        (Bytes::from(compressed), FLAG_GZIP)
+    }
+
+    fn decode(&self, data: Vec<u8>, flags: u32) -> MemCachedResult<Vec<u8>> {
+        if flags & FLAG_GZIP == 0 {
+            return Ok(data);
+        }
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
+/// Raw-deflate-compresses values larger than `threshold` bytes, leaving smaller ones
+/// untouched. Unlike [`GzipCodec`] this carries no gzip header/checksum overhead, at the
+/// cost of the interoperability gzip's self-describing framing gives you.
+pub struct DeflateCodec {
+    threshold: usize,
+}
+
+impl DeflateCodec {
+    pub fn new(threshold: usize) -> DeflateCodec {
+        DeflateCodec { threshold }
+    }
+}
+
+impl ValueCodec for DeflateCodec {
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32) {
+        if raw.len() <= self.threshold {
+            return (Bytes::copy_from_slice(raw), 0);
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw).expect("writing to an in-memory encoder cannot fail");
+        let compressed = encoder.finish().expect("finishing an in-memory encoder cannot fail");
+
+        (Bytes::from(compressed), FLAG_DEFLATE)
+    }
+
+    fn decode(&self, data: Vec<u8>, flags: u32) -> MemCachedResult<Vec<u8>> {
+        if flags & FLAG_DEFLATE == 0 {
+            return Ok(data);
+        }
+
+        let mut decoded = Vec::new();
+        DeflateDecoder::new(&data[..]).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
+/// Tags a value as JSON so that other clients sharing the cache know how to read it.
+///
+/// The value is expected to already be serialized (e.g. with `serde_json::to_vec`); this
+/// codec does not perform the serialization itself, only the flag bookkeeping.
+pub struct JsonCodec;
+
+impl ValueCodec for JsonCodec {
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32) {
+        (Bytes::copy_from_slice(raw), FLAG_JSON)
+    }
+
+    fn decode(&self, data: Vec<u8>, _flags: u32) -> MemCachedResult<Vec<u8>> {
+        Ok(data)
+    }
+}
+
+/// Tags a value as msgpack, analogous to [`JsonCodec`].
+pub struct MsgpackCodec;
+
+impl ValueCodec for MsgpackCodec {
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32) {
+        (Bytes::copy_from_slice(raw), FLAG_MSGPACK)
+    }
+
+    fn decode(&self, data: Vec<u8>, _flags: u32) -> MemCachedResult<Vec<u8>> {
+        Ok(data)
+    }
+}
+
+/// Composes two codecs: `outer` runs last on encode (and therefore first on decode), so
+/// e.g. `ChainCodec::new(GzipCodec::new(256), JsonCodec)` compresses serialized JSON.
+pub struct ChainCodec<O, I> {
+    outer: O,
+    inner: I,
+}
+
+impl<O: ValueCodec, I: ValueCodec> ChainCodec<O, I> {
+    pub fn new(outer: O, inner: I) -> ChainCodec<O, I> {
+        ChainCodec { outer, inner }
+    }
+}
+
+impl<O: ValueCodec, I: ValueCodec> ValueCodec for ChainCodec<O, I> {
+    fn encode(&self, raw: &[u8]) -> (Bytes, u32) {
+        let (data, inner_flags) = self.inner.encode(raw);
+        let (data, outer_flags) = self.outer.encode(&data);
+        (data, inner_flags | outer_flags)
+    }
+
+    fn decode(&self, data: Vec<u8>, flags: u32) -> MemCachedResult<Vec<u8>> {
+        let data = self.outer.decode(data, flags)?;
+        self.inner.decode(data, flags)
+    }
+}
+
+/// Wraps any protocol implementation, running a [`ValueCodec`] over every value that
+/// passes through `set`/`get` (and their CAS, multi, and no-reply counterparts).
+pub struct EncodingProto<P, C: ValueCodec> {
+    inner: P,
+    codec: C,
+}
+
+impl<P, C: ValueCodec> EncodingProto<P, C> {
+    pub fn new(inner: P, codec: C) -> EncodingProto<P, C> {
+        EncodingProto { inner, codec }
+    }
+}
+
+impl<P: Operation, C: ValueCodec> Operation for EncodingProto<P, C> {
+    fn set(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.set(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.add(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> MemCachedResult<()> {
+        self.inner.delete(key)
+    }
+
+    fn replace(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.replace(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn get(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, u32)> {
+        let (data, flags) = self.inner.get(key)?;
+        Ok((self.codec.decode(data, flags)?, flags))
+    }
+
+    fn getk(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, Vec<u8>, u32)> {
+        let (found_key, data, flags) = self.inner.getk(key)?;
+        Ok((found_key, self.codec.decode(data, flags)?, flags))
+    }
+
+    fn increment(&mut self, key: &[u8], amount: u64, initial: u64, expiration: u32) -> MemCachedResult<u64> {
+        self.inner.increment(key, amount, initial, expiration)
+    }
+
+    fn decrement(&mut self, key: &[u8], amount: u64, initial: u64, expiration: u32) -> MemCachedResult<u64> {
+        self.inner.decrement(key, amount, initial, expiration)
+    }
+
+    fn append(&mut self, key: &[u8], value: &[u8]) -> MemCachedResult<()> {
+        // The `flags` stored with the existing value can't be changed by an append, so the
+        // fragment being appended is left exactly as given rather than re-encoded.
+        self.inner.append(key, value)
+    }
+
+    fn prepend(&mut self, key: &[u8], value: &[u8]) -> MemCachedResult<()> {
+        self.inner.prepend(key, value)
+    }
+
+    fn touch(&mut self, key: &[u8], expiration: u32) -> MemCachedResult<()> {
+        self.inner.touch(key, expiration)
+    }
+}
+
+impl<P: MultiOperation, C: ValueCodec> MultiOperation for EncodingProto<P, C> {
+    fn set_multi(&mut self, kv: BTreeMap<&[u8], (&[u8], u32, u32)>) -> MemCachedResult<()> {
+        let mut encoded = BTreeMap::new();
+        let mut owned = Vec::with_capacity(kv.len());
+        for (key, (value, flags, expiration)) in kv {
+            let (data, codec_flags) = self.codec.encode(value);
+            owned.push((key, data, flags | codec_flags, expiration));
+        }
+        for (key, data, flags, expiration) in &owned {
+            encoded.insert(*key, (&data[..], *flags, *expiration));
+        }
+        self.inner.set_multi(encoded)
+    }
+
+    fn delete_multi(&mut self, keys: &[&[u8]]) -> MemCachedResult<()> {
+        self.inner.delete_multi(keys)
+    }
+
+    fn increment_multi<'a>(
+        &mut self,
+        kv: HashMap<&'a [u8], (u64, u64, u32)>,
+    ) -> MemCachedResult<HashMap<&'a [u8], u64>> {
+        self.inner.increment_multi(kv)
+    }
+
+    fn get_multi(&mut self, keys: &[&[u8]]) -> MemCachedResult<HashMap<Vec<u8>, (Vec<u8>, u32)>> {
+        let values = self.inner.get_multi(keys)?;
+        values
+            .into_iter()
+            .map(|(key, (data, flags))| Ok((key, (self.codec.decode(data, flags)?, flags))))
+            .collect()
+    }
+}
+
+impl<P: CasOperation, C: ValueCodec> CasOperation for EncodingProto<P, C> {
+    fn set_cas(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32, cas: u64) -> MemCachedResult<u64> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.set_cas(key, &encoded, flags | codec_flags, expiration, cas)
+    }
+
+    fn add_cas(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<u64> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.add_cas(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn replace_cas(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32, cas: u64) -> MemCachedResult<u64> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.replace_cas(key, &encoded, flags | codec_flags, expiration, cas)
+    }
+
+    fn get_cas(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, u32, u64)> {
+        let (data, flags, cas) = self.inner.get_cas(key)?;
+        Ok((self.codec.decode(data, flags)?, flags, cas))
+    }
+
+    fn getk_cas(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, Vec<u8>, u32, u64)> {
+        let (found_key, data, flags, cas) = self.inner.getk_cas(key)?;
+        Ok((found_key, self.codec.decode(data, flags)?, flags, cas))
+    }
+
+    fn increment_cas(
+        &mut self,
+        key: &[u8],
+        amount: u64,
+        initial: u64,
+        expiration: u32,
+        cas: u64,
+    ) -> MemCachedResult<(u64, u64)> {
+        self.inner.increment_cas(key, amount, initial, expiration, cas)
+    }
+
+    fn decrement_cas(
+        &mut self,
+        key: &[u8],
+        amount: u64,
+        initial: u64,
+        expiration: u32,
+        cas: u64,
+    ) -> MemCachedResult<(u64, u64)> {
+        self.inner.decrement_cas(key, amount, initial, expiration, cas)
+    }
+
+    fn append_cas(&mut self, key: &[u8], value: &[u8], cas: u64) -> MemCachedResult<u64> {
+        self.inner.append_cas(key, value, cas)
+    }
+
+    fn prepend_cas(&mut self, key: &[u8], value: &[u8], cas: u64) -> MemCachedResult<u64> {
+        self.inner.prepend_cas(key, value, cas)
+    }
+
+    fn touch_cas(&mut self, key: &[u8], expiration: u32, cas: u64) -> MemCachedResult<u64> {
+        self.inner.touch_cas(key, expiration, cas)
+    }
+}
+
+impl<P: NoReplyOperation, C: ValueCodec> NoReplyOperation for EncodingProto<P, C> {
+    fn set_noreply(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.set_noreply(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn add_noreply(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.add_noreply(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn delete_noreply(&mut self, key: &[u8]) -> MemCachedResult<()> {
+        self.inner.delete_noreply(key)
+    }
+
+    fn replace_noreply(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
+        let (encoded, codec_flags) = self.codec.encode(value);
+        self.inner.replace_noreply(key, &encoded, flags | codec_flags, expiration)
+    }
+
+    fn increment_noreply(&mut self, key: &[u8], amount: u64, initial: u64, expiration: u32) -> MemCachedResult<()> {
+        self.inner.increment_noreply(key, amount, initial, expiration)
+    }
+
+    fn decrement_noreply(&mut self, key: &[u8], amount: u64, initial: u64, expiration: u32) -> MemCachedResult<()> {
+        self.inner.decrement_noreply(key, amount, initial, expiration)
+    }
+
+    fn append_noreply(&mut self, key: &[u8], value: &[u8]) -> MemCachedResult<()> {
+        self.inner.append_noreply(key, value)
+    }
+
+    fn prepend_noreply(&mut self, key: &[u8], value: &[u8]) -> MemCachedResult<()> {
+        self.inner.prepend_noreply(key, value)
+    }
+}
+
+impl<P: ServerOperation, C: ValueCodec> ServerOperation for EncodingProto<P, C> {
+    fn quit(&mut self) -> MemCachedResult<()> {
+        self.inner.quit()
+    }
+
+    fn flush(&mut self, expiration: u32) -> MemCachedResult<()> {
+        self.inner.flush(expiration)
+    }
+
+    fn noop(&mut self) -> MemCachedResult<()> {
+        self.inner.noop()
+    }
+
+    fn version(&mut self) -> MemCachedResult<semver::Version> {
+        self.inner.version()
+    }
+
+    fn stat(&mut self) -> MemCachedResult<BTreeMap<String, String>> {
+        self.inner.stat()
+    }
+}
+
+impl<P: AuthOperation, C: ValueCodec> AuthOperation for EncodingProto<P, C> {
+    fn list_mechanisms(&mut self) -> MemCachedResult<Vec<String>> {
+        self.inner.list_mechanisms()
+    }
+
+    fn auth_start(&mut self, mech: &str, init: &[u8]) -> MemCachedResult<AuthResponse> {
+        self.inner.auth_start(mech, init)
+    }
+
+    fn auth_continue(&mut self, mech: &str, data: &[u8]) -> MemCachedResult<AuthResponse> {
+        self.inner.auth_continue(mech, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_codec_passes_small_values_through_untouched() {
+        let codec = GzipCodec::new(1024);
+        let (data, flags) = codec.encode(b"short");
+        assert_eq!(flags, 0);
+        assert_eq!(&data[..], b"short");
+    }
+
+    #[test]
+    fn gzip_codec_compresses_and_round_trips_large_values() {
+        let codec = GzipCodec::new(4);
+        let raw = b"a value definitely longer than the four byte threshold";
+        let (data, flags) = codec.encode(raw);
+        assert_eq!(flags, FLAG_GZIP);
+        assert_ne!(&data[..], &raw[..]);
+
+        let decoded = codec.decode(data.to_vec(), flags).unwrap();
+        assert_eq!(decoded, raw);
+    }
+
+    #[test]
+    fn deflate_codec_compresses_and_round_trips_large_values() {
+        let codec = DeflateCodec::new(4);
+        let raw = b"a value definitely longer than the four byte threshold";
+        let (data, flags) = codec.encode(raw);
+        assert_eq!(flags, FLAG_DEFLATE);
+        assert_ne!(&data[..], &raw[..]);
+
+        let decoded = codec.decode(data.to_vec(), flags).unwrap();
+        assert_eq!(decoded, raw);
+    }
+
+    #[test]
+    fn chain_codec_composes_flags_and_round_trips() {
+        let codec = ChainCodec::new(GzipCodec::new(4), JsonCodec);
+        let raw = br#"{"a":1}"#;
+        let (data, flags) = codec.encode(raw);
+        assert_eq!(flags, FLAG_GZIP | FLAG_JSON);
+
+        let decoded = codec.decode(data.to_vec(), flags).unwrap();
+        assert_eq!(decoded, raw);
+    }
+
+    /// A minimal in-memory `Operation` backend, just enough to prove `EncodingProto` wires
+    /// the codec through `set`/`get` correctly. Methods this suite doesn't exercise panic.
+    struct FakeStore {
+        values: HashMap<Vec<u8>, (Vec<u8>, u32)>,
+    }
+
+    impl Operation for FakeStore {
+        fn set(&mut self, key: &[u8], value: &[u8], flags: u32, _expiration: u32) -> MemCachedResult<()> {
+            self.values.insert(key.to_vec(), (value.to_vec(), flags));
+            Ok(())
+        }
+
+        fn add(&mut self, _key: &[u8], _value: &[u8], _flags: u32, _expiration: u32) -> MemCachedResult<()> {
+            unimplemented!()
+        }
+
+        fn delete(&mut self, _key: &[u8]) -> MemCachedResult<()> {
+            unimplemented!()
+        }
+
+        fn replace(&mut self, _key: &[u8], _value: &[u8], _flags: u32, _expiration: u32) -> MemCachedResult<()> {
+            unimplemented!()
+        }
+
+        fn get(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, u32)> {
+            Ok(self.values.get(key).expect("missing key in FakeStore").clone())
+        }
+
+        fn getk(&mut self, _key: &[u8]) -> MemCachedResult<(Vec<u8>, Vec<u8>, u32)> {
+            unimplemented!()
+        }
+
+        fn increment(&mut self, _key: &[u8], _amount: u64, _initial: u64, _expiration: u32) -> MemCachedResult<u64> {
+            unimplemented!()
+        }
+
+        fn decrement(&mut self, _key: &[u8], _amount: u64, _initial: u64, _expiration: u32) -> MemCachedResult<u64> {
+            unimplemented!()
+        }
+
+        fn append(&mut self, _key: &[u8], _value: &[u8]) -> MemCachedResult<()> {
+            unimplemented!()
+        }
+
+        fn prepend(&mut self, _key: &[u8], _value: &[u8]) -> MemCachedResult<()> {
+            unimplemented!()
+        }
+
+        fn touch(&mut self, _key: &[u8], _expiration: u32) -> MemCachedResult<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn encoding_proto_set_ors_codec_flags_into_the_stored_flags() {
+        let mut proto = EncodingProto::new(FakeStore { values: HashMap::new() }, GzipCodec::new(4));
+        let raw = b"a value definitely longer than the four byte threshold";
+
+        proto.set(b"key", raw, 0b1, 0).unwrap();
+
+        let (stored, flags) = proto.inner.values.get(b"key".as_ref()).unwrap().clone();
+        assert_eq!(flags, 0b1 | FLAG_GZIP);
+        assert_ne!(stored, raw);
+    }
+
+    #[test]
+    fn encoding_proto_get_decodes_using_the_flags_returned_by_the_backend() {
+        let mut proto = EncodingProto::new(FakeStore { values: HashMap::new() }, GzipCodec::new(4));
+        let raw = b"a value definitely longer than the four byte threshold";
+
+        proto.set(b"key", raw, 0, 0).unwrap();
+        let (value, flags) = proto.get(b"key").unwrap();
+
+        assert_eq!(value, raw);
+        assert_eq!(flags, FLAG_GZIP);
+    }
+}
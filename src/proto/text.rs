@@ -10,7 +10,7 @@
 use std::collections::{BTreeMap, HashMap};
 use std::error;
 use std::fmt;
-use std::io::{BufRead, BufReader, Cursor, Write};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
 use std::str;
 use std::string::String;
 
@@ -76,61 +76,225 @@ impl<T: BufRead + Write + Send> TextProto<T> {
     fn send_noop(&mut self) -> MemCachedResult<u32> {
         panic!("NoOp command no supported for text protocol.")
     }
+
+    /// Read a single `\r\n`-terminated line, with the terminator stripped.
+    fn read_line(&mut self) -> MemCachedResult<String> {
+        let mut line = String::new();
+        self.stream.read_line(&mut line)?;
+        let len = line.trim_end_matches("\r\n").len();
+        line.truncate(len);
+        Ok(line)
+    }
+
+    /// Read exactly `len` bytes of a data block followed by its `\r\n` terminator.
+    fn read_data_block(&mut self, len: usize) -> MemCachedResult<Vec<u8>> {
+        let mut data = vec![0u8; len];
+        self.stream.read_exact(&mut data)?;
+
+        let mut crlf = [0u8; 2];
+        self.stream.read_exact(&mut crlf)?;
+
+        Ok(data)
+    }
+
+    /// Validate that `key` is UTF-8, returning it as a `&str` for formatting into a command
+    /// line. Only UTF-8 validity is checked; keys with spaces or control characters are not
+    /// rejected.
+    fn key_str(key: &[u8]) -> MemCachedResult<&str> {
+        str::from_utf8(key).map_err(|_| {
+            Error::from_status(Status::Error, Some("memcached text-protocol keys must be valid UTF-8".to_owned())).into()
+        })
+    }
+
+    /// Parse an RFC4616 PLAIN `init` payload (`authzid\0authcid\0passwd`) into `(username, password)`.
+    fn parse_plain_init(init: &[u8]) -> MemCachedResult<(&str, &str)> {
+        let init = str::from_utf8(init)
+            .map_err(|_| Error::from_status(Status::Error, Some("PLAIN auth payload must be valid UTF-8".to_owned())))?;
+
+        let mut parts = init.split('\0');
+        parts
+            .next()
+            .ok_or_else(|| Error::from_status(Status::Error, Some("malformed PLAIN auth payload".to_owned())))?; // authzid, ignored
+        let username = parts
+            .next()
+            .ok_or_else(|| Error::from_status(Status::Error, Some("malformed PLAIN auth payload".to_owned())))?;
+        let password = parts
+            .next()
+            .ok_or_else(|| Error::from_status(Status::Error, Some("malformed PLAIN auth payload".to_owned())))?;
+
+        Ok((username, password))
+    }
+
+    /// Parse a `VALUE <key> <flags> <bytes>` header line, returning `(key, flags, bytes)`.
+    fn parse_value_header(line: &str) -> MemCachedResult<(String, u32, usize)> {
+        let mut parts = line.split(' ');
+        parts.next(); // "VALUE"
+        let key = parts.next().ok_or_else(|| Error::from_status(Status::Error, None))?;
+        let flags = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::from_status(Status::Error, None))?;
+        let bytes = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::from_status(Status::Error, None))?;
+
+        Ok((key.to_owned(), flags, bytes))
+    }
+
+    fn store_command(
+        &mut self,
+        cmd: &str,
+        key: &[u8],
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+    ) -> MemCachedResult<()> {
+        write!(
+            self.stream,
+            "{} {} {} {} {}\r\n",
+            cmd,
+            Self::key_str(key)?,
+            flags,
+            expiration,
+            value.len()
+        )?;
+        self.stream.write_all(value)?;
+        self.stream.write_all(b"\r\n")?;
+        self.stream.flush()?;
+
+        match self.read_line()?.as_str() {
+            "STORED" => Ok(()),
+            "NOT_STORED" => Err(Error::from_status(Status::NotStored, None).into()),
+            "EXISTS" => Err(Error::from_status(Status::Exists, None).into()),
+            "NOT_FOUND" => Err(Error::from_status(Status::NotFound, None).into()),
+            reply => Err(Error::from_status(Status::Error, Some(reply.to_owned())).into()),
+        }
+    }
+
+    fn incr_decr_command(
+        &mut self,
+        cmd: &str,
+        key: &[u8],
+        amount: u64,
+        initial: u64,
+        expiration: u32,
+    ) -> MemCachedResult<u64> {
+        write!(self.stream, "{} {} {}\r\n", cmd, Self::key_str(key)?, amount)?;
+        self.stream.flush()?;
+
+        let reply = self.read_line()?;
+        match reply.as_str() {
+            "NOT_FOUND" => {
+                if initial != 0 || expiration != 0 {
+                    Err(Error::from_status(
+                        Status::NotFound,
+                        Some("the text protocol cannot set an initial value or expiration on incr/decr".to_owned()),
+                    )
+                    .into())
+                } else {
+                    Err(Error::from_status(Status::NotFound, None).into())
+                }
+            }
+            value => value
+                .parse()
+                .map_err(|_| Error::from_status(Status::Error, Some(value.to_owned())).into()),
+        }
+    }
 }
 
 impl<T: BufRead + Write + Send> Operation for TextProto<T> {
     fn set(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
-        panic!("Set command no supported for text protocol.")
+        self.store_command("set", key, value, flags, expiration)
     }
 
     fn add(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
-        panic!("Add command no supported for text protocol.")
+        self.store_command("add", key, value, flags, expiration)
     }
 
     fn delete(&mut self, key: &[u8]) -> MemCachedResult<()> {
-        panic!("Delete command no supported for text protocol.")
+        write!(self.stream, "delete {}\r\n", Self::key_str(key)?)?;
+        self.stream.flush()?;
+
+        match self.read_line()?.as_str() {
+            "DELETED" => Ok(()),
+            "NOT_FOUND" => Err(Error::from_status(Status::NotFound, None).into()),
+            reply => Err(Error::from_status(Status::Error, Some(reply.to_owned())).into()),
+        }
     }
 
     fn replace(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<()> {
-        panic!("Replace command no supported for text protocol.")
+        self.store_command("replace", key, value, flags, expiration)
     }
 
     fn get(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, u32)> {
-        panic!("get command no supported for text protocol.")
+        let (_, value, flags) = self.getk(key)?;
+        Ok((value, flags))
     }
 
     fn getk(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, Vec<u8>, u32)> {
-        panic!("getk command no supported for text protocol.")
+        write!(self.stream, "get {}\r\n", Self::key_str(key)?)?;
+        self.stream.flush()?;
+
+        let header = self.read_line()?;
+        if header == "END" {
+            return Err(Error::from_status(Status::NotFound, None).into());
+        }
+
+        let (found_key, flags, bytes) = Self::parse_value_header(&header)?;
+        let data = self.read_data_block(bytes)?;
+        self.read_line()?; // "END"
+
+        Ok((found_key.into_bytes(), data, flags))
     }
 
     fn increment(&mut self, key: &[u8], amount: u64, initial: u64, expiration: u32) -> MemCachedResult<u64> {
-        panic!("increment command no supported for text protocol.")
+        self.incr_decr_command("incr", key, amount, initial, expiration)
     }
 
     fn decrement(&mut self, key: &[u8], amount: u64, initial: u64, expiration: u32) -> MemCachedResult<u64> {
-        panic!("decrement command no supported for text protocol.")
+        self.incr_decr_command("decr", key, amount, initial, expiration)
     }
 
     fn append(&mut self, key: &[u8], value: &[u8]) -> MemCachedResult<()> {
-        panic!("append command no supported for text protocol.")
+        self.store_command("append", key, value, 0, 0)
     }
 
     fn prepend(&mut self, key: &[u8], value: &[u8]) -> MemCachedResult<()> {
-        panic!("prepend command no supported for text protocol.")
+        self.store_command("prepend", key, value, 0, 0)
     }
 
     fn touch(&mut self, key: &[u8], expiration: u32) -> MemCachedResult<()> {
-        panic!("touch command no supported for text protocol.")
+        write!(self.stream, "touch {} {}\r\n", Self::key_str(key)?, expiration)?;
+        self.stream.flush()?;
+
+        match self.read_line()?.as_str() {
+            "TOUCHED" => Ok(()),
+            "NOT_FOUND" => Err(Error::from_status(Status::NotFound, None).into()),
+            reply => Err(Error::from_status(Status::Error, Some(reply.to_owned())).into()),
+        }
     }
 }
 
 impl<T: BufRead + Write + Send> ServerOperation for TextProto<T> {
     fn quit(&mut self) -> MemCachedResult<()> {
-        panic!("quit command no supported for text protocol.")
+        self.stream.write_all(b"quit\r\n")?;
+        self.stream.flush()?;
+        Ok(())
     }
 
     fn flush(&mut self, expiration: u32) -> MemCachedResult<()> {
-        panic!("flush command no supported for text protocol.")
+        if expiration == 0 {
+            self.stream.write_all(b"flush_all\r\n")?;
+        } else {
+            write!(self.stream, "flush_all {}\r\n", expiration)?;
+        }
+        self.stream.flush()?;
+
+        match self.read_line()?.as_str() {
+            "OK" => Ok(()),
+            reply => Err(Error::from_status(Status::Error, Some(reply.to_owned())).into()),
+        }
     }
 
     fn noop(&mut self) -> MemCachedResult<()> {
@@ -138,21 +302,72 @@ impl<T: BufRead + Write + Send> ServerOperation for TextProto<T> {
     }
 
     fn version(&mut self) -> MemCachedResult<Version> {
-        panic!("version command no supported for text protocol.")
+        self.stream.write_all(b"version\r\n")?;
+        self.stream.flush()?;
+
+        let line = self.read_line()?;
+        let raw = line
+            .strip_prefix("VERSION ")
+            .ok_or_else(|| Error::from_status(Status::Error, Some(line.clone())))?;
+
+        Version::parse(raw.trim())
+            .map_err(|e| Error::from_status(Status::Error, Some(e.to_string())).into())
     }
 
     fn stat(&mut self) -> MemCachedResult<BTreeMap<String, String>> {
-        panic!("stat command no supported for text protocol.")
+        self.stream.write_all(b"stats\r\n")?;
+        self.stream.flush()?;
+
+        let mut stats = BTreeMap::new();
+        loop {
+            let line = self.read_line()?;
+            if line == "END" {
+                break;
+            }
+
+            let mut parts = line.splitn(3, ' ');
+            parts.next(); // "STAT"
+            let name = parts
+                .next()
+                .ok_or_else(|| Error::from_status(Status::Error, Some(line.clone())))?;
+            let value = parts
+                .next()
+                .ok_or_else(|| Error::from_status(Status::Error, Some(line.clone())))?;
+            stats.insert(name.to_owned(), value.to_owned());
+        }
+
+        Ok(stats)
     }
 }
 
 impl<T: BufRead + Write + Send> MultiOperation for TextProto<T> {
     fn set_multi(&mut self, kv: BTreeMap<&[u8], (&[u8], u32, u32)>) -> MemCachedResult<()> {
-        panic!("set_multi command no supported for text protocol.")
+        for (key, (value, flags, expiration)) in kv {
+            write!(
+                self.stream,
+                "set {} {} {} {} noreply\r\n",
+                Self::key_str(key)?,
+                flags,
+                expiration,
+                value.len()
+            )?;
+            self.stream.write_all(value)?;
+            self.stream.write_all(b"\r\n")?;
+        }
+        self.stream.flush()?;
+
+        // `noreply` suppresses per-command replies, so round-trip a `version`
+        // to make sure the pipeline drained and surface any protocol error.
+        self.version().map(|_| ())
     }
 
     fn delete_multi(&mut self, keys: &[&[u8]]) -> MemCachedResult<()> {
-        panic!("delete_multi command no supported for text protocol.")
+        for key in keys {
+            write!(self.stream, "delete {} noreply\r\n", Self::key_str(key)?)?;
+        }
+        self.stream.flush()?;
+
+        self.version().map(|_| ())
     }
 
     fn increment_multi<'a>(
@@ -163,7 +378,27 @@ impl<T: BufRead + Write + Send> MultiOperation for TextProto<T> {
     }
 
     fn get_multi(&mut self, keys: &[&[u8]]) -> MemCachedResult<HashMap<Vec<u8>, (Vec<u8>, u32)>> {
-        panic!("get_multi command no supported for text protocol.")
+        let key_list = keys
+            .iter()
+            .map(|k| Self::key_str(k))
+            .collect::<MemCachedResult<Vec<_>>>()?
+            .join(" ");
+        write!(self.stream, "get {}\r\n", key_list)?;
+        self.stream.flush()?;
+
+        let mut values = HashMap::new();
+        loop {
+            let header = self.read_line()?;
+            if header == "END" {
+                break;
+            }
+
+            let (found_key, flags, bytes) = Self::parse_value_header(&header)?;
+            let data = self.read_data_block(bytes)?;
+            values.insert(found_key.into_bytes(), (data, flags));
+        }
+
+        Ok(values)
     }
 }
 
@@ -201,25 +436,102 @@ impl<T: BufRead + Write + Send> NoReplyOperation for TextProto<T> {
     }
 }
 
+impl<T: BufRead + Write + Send> TextProto<T> {
+    /// Parse a `VALUE <key> <flags> <bytes> <cas_unique>` header line into `(key, flags, bytes, cas)`.
+    fn parse_cas_value_header(line: &str) -> MemCachedResult<(String, u32, usize, u64)> {
+        let mut parts = line.split(' ');
+        parts.next(); // "VALUE"
+        let key = parts.next().ok_or_else(|| Error::from_status(Status::Error, None))?;
+        let flags = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::from_status(Status::Error, None))?;
+        let bytes = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::from_status(Status::Error, None))?;
+        let cas = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::from_status(Status::Error, None))?;
+
+        Ok((key.to_owned(), flags, bytes, cas))
+    }
+
+    fn cas_store_command(
+        &mut self,
+        cmd: &str,
+        key: &[u8],
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+        cas: u64,
+    ) -> MemCachedResult<u64> {
+        write!(
+            self.stream,
+            "{} {} {} {} {} {}\r\n",
+            cmd,
+            Self::key_str(key)?,
+            flags,
+            expiration,
+            value.len(),
+            cas
+        )?;
+        self.stream.write_all(value)?;
+        self.stream.write_all(b"\r\n")?;
+        self.stream.flush()?;
+
+        match self.read_line()?.as_str() {
+            // The ASCII protocol does not echo a fresh cas token on a successful store.
+            // Re-fetching it with a follow-up `gets` would race another client's store,
+            // delete, or flush in between, so return the token the caller supplied instead.
+            "STORED" => Ok(cas),
+            "EXISTS" => Err(Error::from_status(Status::Exists, None).into()),
+            "NOT_FOUND" => Err(Error::from_status(Status::NotFound, None).into()),
+            reply => Err(Error::from_status(Status::Error, Some(reply.to_owned())).into()),
+        }
+    }
+}
+
 impl<T: BufRead + Write + Send> CasOperation for TextProto<T> {
+    /// The ASCII protocol's `cas` command does not echo a fresh `cas_unique` on `STORED`, so
+    /// the returned token is the one the caller supplied, **not** the item's actual new cas
+    /// value the server just minted. A read-modify-write loop that feeds this return value
+    /// straight into the next `set_cas`/`replace_cas` call will spuriously get back `EXISTS`;
+    /// callers that need the real post-store token should follow up with `get_cas`.
     fn set_cas(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32, cas: u64) -> MemCachedResult<u64> {
-        panic!("set_cas command no supported for text protocol.")
+        self.cas_store_command("cas", key, value, flags, expiration, cas)
     }
 
     fn add_cas(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32) -> MemCachedResult<u64> {
         panic!("add_cas command no supported for text protocol.")
     }
 
+    /// See the caveat on `set_cas` in this impl: the returned token is the caller-supplied
+    /// `cas`, not a fresh one from the server.
     fn replace_cas(&mut self, key: &[u8], value: &[u8], flags: u32, expiration: u32, cas: u64) -> MemCachedResult<u64> {
-        panic!("replace_cas command no supported for text protocol.")
+        self.cas_store_command("cas", key, value, flags, expiration, cas)
     }
 
     fn get_cas(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, u32, u64)> {
-        panic!("get_cas command no supported for text protocol.")
+        let (_, value, flags, cas) = self.getk_cas(key)?;
+        Ok((value, flags, cas))
     }
 
     fn getk_cas(&mut self, key: &[u8]) -> MemCachedResult<(Vec<u8>, Vec<u8>, u32, u64)> {
-        panic!("getk_cas command no supported for text protocol.")
+        write!(self.stream, "gets {}\r\n", Self::key_str(key)?)?;
+        self.stream.flush()?;
+
+        let header = self.read_line()?;
+        if header == "END" {
+            return Err(Error::from_status(Status::NotFound, None).into());
+        }
+
+        let (found_key, flags, bytes, cas) = Self::parse_cas_value_header(&header)?;
+        let data = self.read_data_block(bytes)?;
+        self.read_line()?; // "END"
+
+        Ok((found_key.into_bytes(), data, flags, cas))
     }
 
     fn increment_cas(
@@ -259,14 +571,174 @@ impl<T: BufRead + Write + Send> CasOperation for TextProto<T> {
 
 impl<T: BufRead + Write + Send> AuthOperation for TextProto<T> {
     fn list_mechanisms(&mut self) -> MemCachedResult<Vec<String>> {
-        panic!("list_mechanisms command no supported for text protocol.")
+        Ok(vec!["PLAIN".to_owned()])
     }
 
+    // Text-protocol SASL has no wire support of its own: servers that enable it accept
+    // credentials by storing them under the special key `auth`, with `"<username> <password>"`
+    // as the value (see memcached's `sasl_authenticate`).
     fn auth_start(&mut self, mech: &str, init: &[u8]) -> MemCachedResult<AuthResponse> {
-        panic!("auth_start command no supported for text protocol.")
+        if mech != "PLAIN" {
+            return Err(Error::from_status(Status::Error, Some(format!("unsupported auth mechanism: {}", mech))).into());
+        }
+
+        // RFC4616 PLAIN init is NUL-delimited `authzid\0authcid\0passwd`; the `auth` pseudo-key
+        // convention wants a single space between the username and password instead.
+        let (username, password) = Self::parse_plain_init(init)?;
+        let body = format!("{} {}", username, password);
+        self.store_command("set", b"auth", body.as_bytes(), 0, 0)?;
+        Ok(AuthResponse::Complete)
     }
 
-    fn auth_continue(&mut self, mech: &str, data: &[u8]) -> MemCachedResult<AuthResponse> {
-        panic!("auth_continue command no supported for text protocol.")
+    fn auth_continue(&mut self, _mech: &str, _data: &[u8]) -> MemCachedResult<AuthResponse> {
+        Err(Error::from_status(
+            Status::Error,
+            Some("PLAIN auth completes in a single step over the text protocol".to_owned()),
+        )
+        .into())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    type MockStream = Cursor<Vec<u8>>;
+
+    /// Builds a loopback stream for a single request/response exchange: the command
+    /// bytes double as the region `TextProto`'s write will land on, so the cursor is left
+    /// positioned right at the start of `response` once the command has been written.
+    fn mock(command: &[u8], response: &[u8]) -> TextProto<MockStream> {
+        let mut buf = Vec::with_capacity(command.len() + response.len());
+        buf.extend_from_slice(command);
+        buf.extend_from_slice(response);
+        TextProto::new(Cursor::new(buf))
+    }
+
+    #[test]
+    fn parse_value_header_parses_key_flags_and_length() {
+        let (key, flags, bytes) = TextProto::<MockStream>::parse_value_header("VALUE foo 42 3").unwrap();
+        assert_eq!(key, "foo");
+        assert_eq!(flags, 42);
+        assert_eq!(bytes, 3);
+    }
+
+    #[test]
+    fn parse_value_header_rejects_truncated_line() {
+        assert!(TextProto::<MockStream>::parse_value_header("VALUE foo 42").is_err());
+    }
+
+    #[test]
+    fn key_str_rejects_non_utf8_keys() {
+        assert!(TextProto::<MockStream>::key_str(&[0xff, 0xfe]).is_err());
+        assert_eq!(TextProto::<MockStream>::key_str(b"foo").unwrap(), "foo");
+    }
+
+    #[test]
+    fn get_round_trip() {
+        let mut proto = mock(b"get foo\r\n", b"VALUE foo 0 3\r\nbar\r\nEND\r\n");
+        let (value, flags) = proto.get(b"foo").unwrap();
+        assert_eq!(value, b"bar");
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn get_not_found() {
+        let mut proto = mock(b"get foo\r\n", b"END\r\n");
+        assert_eq!(proto.get(b"foo").unwrap_err().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn stat_reads_until_end() {
+        let mut proto = mock(b"stats\r\n", b"STAT pid 123\r\nSTAT version 1.6.6\r\nEND\r\n");
+        let stats = proto.stat().unwrap();
+        assert_eq!(stats.get("pid").map(String::as_str), Some("123"));
+        assert_eq!(stats.get("version").map(String::as_str), Some("1.6.6"));
+        assert_eq!(stats.len(), 2);
+    }
+
+    #[test]
+    fn parse_cas_value_header_parses_key_flags_length_and_cas() {
+        let (key, flags, bytes, cas) = TextProto::<MockStream>::parse_cas_value_header("VALUE foo 42 3 7").unwrap();
+        assert_eq!(key, "foo");
+        assert_eq!(flags, 42);
+        assert_eq!(bytes, 3);
+        assert_eq!(cas, 7);
+    }
+
+    #[test]
+    fn parse_cas_value_header_rejects_truncated_line() {
+        assert!(TextProto::<MockStream>::parse_cas_value_header("VALUE foo 42 3").is_err());
+    }
+
+    #[test]
+    fn get_cas_round_trip() {
+        let mut proto = mock(b"gets foo\r\n", b"VALUE foo 0 3 7\r\nbar\r\nEND\r\n");
+        let (value, flags, cas) = proto.get_cas(b"foo").unwrap();
+        assert_eq!(value, b"bar");
+        assert_eq!(flags, 0);
+        assert_eq!(cas, 7);
+    }
+
+    #[test]
+    fn set_cas_returns_supplied_token_on_stored_without_refetching() {
+        // Only the `cas` store command's reply is on the wire; if `set_cas` tried to
+        // re-fetch the token with a follow-up `gets` it would fail reading past the buffer.
+        let mut proto = mock(b"cas foo 0 0 3 7\r\nbar\r\n", b"STORED\r\n");
+        let cas = proto.set_cas(b"foo", b"bar", 0, 0, 7).unwrap();
+        assert_eq!(cas, 7);
+    }
+
+    #[test]
+    fn set_cas_exists_on_conflicting_token() {
+        let mut proto = mock(b"cas foo 0 0 3 7\r\nbar\r\n", b"EXISTS\r\n");
+        assert_eq!(proto.set_cas(b"foo", b"bar", 0, 0, 7).unwrap_err().status(), Status::Exists);
+    }
+
+    #[test]
+    fn auth_start_reformats_plain_init_with_a_space() {
+        let init = b"\0alice\0hunter2";
+        let mut proto = mock(b"set auth 0 0 13\r\nalice hunter2\r\n", b"STORED\r\n");
+        proto.auth_start("PLAIN", init).unwrap();
+    }
+
+    #[test]
+    fn auth_start_rejects_malformed_plain_init() {
+        let mut proto = mock(b"", b"");
+        assert!(proto.auth_start("PLAIN", b"no-nuls-here").is_err());
+    }
+
+    #[test]
+    fn set_multi_pipelines_noreply_commands_and_syncs_with_version() {
+        let mut kv = BTreeMap::new();
+        kv.insert(&b"a"[..], (&b"1"[..], 0u32, 0u32));
+        kv.insert(&b"b"[..], (&b"22"[..], 0u32, 0u32));
+
+        let command = b"set a 0 0 1 noreply\r\n1\r\nset b 0 0 2 noreply\r\n22\r\nversion\r\n";
+        let mut proto = mock(command, b"VERSION 1.6.6\r\n");
+        proto.set_multi(kv).unwrap();
+    }
+
+    #[test]
+    fn delete_multi_pipelines_noreply_deletes_and_syncs_with_version() {
+        let keys: Vec<&[u8]> = vec![b"a", b"b"];
+
+        let command = b"delete a noreply\r\ndelete b noreply\r\nversion\r\n";
+        let mut proto = mock(command, b"VERSION 1.6.6\r\n");
+        proto.delete_multi(&keys).unwrap();
+    }
+
+    #[test]
+    fn get_multi_parses_interleaved_values() {
+        let keys: Vec<&[u8]> = vec![b"a", b"b"];
+
+        let command = b"get a b\r\n";
+        let response = b"VALUE a 0 1\r\n1\r\nVALUE b 0 2\r\n22\r\nEND\r\n";
+        let mut proto = mock(command, response);
+
+        let values = proto.get_multi(&keys).unwrap();
+        assert_eq!(values.get(b"a".as_ref()), Some(&(b"1".to_vec(), 0)));
+        assert_eq!(values.get(b"b".as_ref()), Some(&(b"22".to_vec(), 0)));
+        assert_eq!(values.len(), 2);
+    }
+}